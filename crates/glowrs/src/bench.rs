@@ -0,0 +1,163 @@
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use hf_hub::api::sync::Api;
+use hf_hub::{Repo, RepoType};
+use serde::{Deserialize, Serialize};
+
+use crate::model::embedder::{
+    encode_batch_with_usage, load_model_and_tokenizer, EmbedderType, PoolingMode, WeightSource,
+};
+
+/// A workload file: a list of independent scenarios to benchmark back to back.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Workload {
+    pub scenarios: Vec<Scenario>,
+}
+
+/// One benchmark scenario — a model pinned to a revision, exercised over a set
+/// of batch sizes.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    /// HF repository, e.g. `sentence-transformers/all-MiniLM-L6-v2`.
+    pub model: String,
+    /// Repository revision (branch, tag or commit); defaults to `main`.
+    #[serde(default = "default_revision")]
+    pub revision: String,
+    pub embedder_type: EmbedderType,
+    /// Batch sizes to measure; each is run independently.
+    pub batch_sizes: Vec<usize>,
+    #[serde(default)]
+    pub normalize: bool,
+    /// Measured iterations per batch size.
+    pub iterations: usize,
+    /// Unmeasured warmup iterations run before timing starts.
+    #[serde(default)]
+    pub warmup: usize,
+    #[serde(default)]
+    pub weight_source: WeightSource,
+    /// Sentence repeated to fill each batch; a short English sentence by
+    /// default.
+    #[serde(default = "default_sample")]
+    pub sample: String,
+}
+
+fn default_revision() -> String {
+    "main".to_string()
+}
+
+fn default_sample() -> String {
+    "The quick brown fox jumps over the lazy dog.".to_string()
+}
+
+/// Throughput and latency for a single batch size within a scenario.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub batch_size: usize,
+    pub iterations: usize,
+    pub p50_latency_ms: f64,
+    pub p95_latency_ms: f64,
+    pub sentences_per_sec: f64,
+    pub tokens_per_sec: f64,
+}
+
+/// Aggregate results for a single scenario.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScenarioResult {
+    pub model: String,
+    pub revision: String,
+    pub model_load_ms: f64,
+    pub batches: Vec<BatchResult>,
+}
+
+/// Load a model once and measure `encode_batch_with_usage` across every batch
+/// size declared in the scenario.
+pub fn run_scenario(scenario: &Scenario) -> Result<ScenarioResult> {
+    let api = Api::new()?.repo(Repo::with_revision(
+        scenario.model.clone(),
+        RepoType::Model,
+        scenario.revision.clone(),
+    ));
+
+    let load_start = Instant::now();
+    let (model, tokenizer) =
+        load_model_and_tokenizer(api, scenario.embedder_type, scenario.weight_source)
+            .with_context(|| format!("Failed to load `{}`.", scenario.model))?;
+    let model_load_ms = load_start.elapsed().as_secs_f64() * 1e3;
+
+    let mut batches = Vec::with_capacity(scenario.batch_sizes.len());
+    for &batch_size in &scenario.batch_sizes {
+        let sentences: Vec<String> = (0..batch_size).map(|_| scenario.sample.clone()).collect();
+
+        for _ in 0..scenario.warmup {
+            encode_batch_with_usage(
+                model.as_ref(),
+                &tokenizer,
+                sentences.clone(),
+                scenario.normalize,
+                PoolingMode::default(),
+            )?;
+        }
+
+        let mut latencies_ms = Vec::with_capacity(scenario.iterations);
+        let mut total_tokens: u64 = 0;
+        let measured_start = Instant::now();
+        for _ in 0..scenario.iterations {
+            let iter_start = Instant::now();
+            let (_embeddings, usage) = encode_batch_with_usage(
+                model.as_ref(),
+                &tokenizer,
+                sentences.clone(),
+                scenario.normalize,
+                PoolingMode::default(),
+            )?;
+            latencies_ms.push(iter_start.elapsed().as_secs_f64() * 1e3);
+            total_tokens += usage.total_tokens as u64;
+        }
+        let measured_secs = measured_start.elapsed().as_secs_f64().max(f64::EPSILON);
+
+        batches.push(BatchResult {
+            batch_size,
+            iterations: scenario.iterations,
+            p50_latency_ms: percentile(&mut latencies_ms, 0.50),
+            p95_latency_ms: percentile(&mut latencies_ms, 0.95),
+            sentences_per_sec: (batch_size * scenario.iterations) as f64 / measured_secs,
+            tokens_per_sec: total_tokens as f64 / measured_secs,
+        });
+    }
+
+    Ok(ScenarioResult {
+        model: scenario.model.clone(),
+        revision: scenario.revision.clone(),
+        model_load_ms,
+        batches,
+    })
+}
+
+/// Run every scenario in a workload, preserving order.
+pub fn run_workload(workload: &Workload) -> Result<Vec<ScenarioResult>> {
+    workload.scenarios.iter().map(run_scenario).collect()
+}
+
+/// Read a JSON workload from `path`, run it, and write the results as JSON to
+/// stdout.
+pub fn run(path: &str) -> Result<()> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read workload file `{path}`."))?;
+    let workload: Workload =
+        serde_json::from_str(&raw).context("Failed to parse workload JSON.")?;
+    let results = run_workload(&workload)?;
+    println!("{}", serde_json::to_string_pretty(&results)?);
+    Ok(())
+}
+
+/// Nearest-rank percentile of the (re-sorted in place) latency samples.
+fn percentile(samples: &mut [f64], quantile: f64) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    samples.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = (quantile * samples.len() as f64).ceil() as usize;
+    let idx = rank.saturating_sub(1).min(samples.len() - 1);
+    samples[idx]
+}
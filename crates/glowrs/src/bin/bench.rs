@@ -0,0 +1,18 @@
+//! Workload-driven embedding benchmark.
+//!
+//! ```text
+//! cargo run --bin bench -- workload.json
+//! ```
+//!
+//! The workload file is a JSON [`Workload`](glowrs::bench::Workload); results
+//! are written as JSON to stdout.
+
+use anyhow::{bail, Result};
+
+fn main() -> Result<()> {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => bail!("usage: bench <workload.json>"),
+    };
+    glowrs::bench::run(&path)
+}
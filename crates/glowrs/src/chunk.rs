@@ -0,0 +1,241 @@
+use std::ops::Range;
+
+use anyhow::{Error, Result};
+use candle_core::Tensor;
+use tokenizers::Tokenizer;
+
+use crate::embedding::sentence_transformer::SentenceTransformer;
+use crate::model::embedder::{encode_batch_with_usage, EmbedderModel, PoolingMode};
+use crate::model::utils::normalize_l2;
+
+/// How to split a long document into overlapping, token-bounded windows.
+///
+/// `chunk_tokens` counts content tokens only, so it must leave headroom below
+/// the model's maximum position embeddings for the `[CLS]`/`[SEP]` special
+/// tokens `encode_batch` adds back when embedding each window — otherwise the
+/// tail of every window is silently truncated, the very failure this module
+/// exists to prevent. Successive windows share `overlap_tokens` tokens so
+/// context straddling a boundary isn't lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkConfig {
+    pub chunk_tokens: usize,
+    pub overlap_tokens: usize,
+}
+
+impl ChunkConfig {
+    pub fn new(chunk_tokens: usize, overlap_tokens: usize) -> Self {
+        Self {
+            chunk_tokens,
+            overlap_tokens,
+        }
+    }
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        // 510 content tokens leaves room for `[CLS]`/`[SEP]` under the common
+        // 512-token limit once `encode_batch` re-adds the special tokens.
+        Self {
+            chunk_tokens: 510,
+            overlap_tokens: 64,
+        }
+    }
+}
+
+/// A single decoded window of a document together with the half-open range of
+/// source token ids it was produced from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub text: String,
+    pub token_range: Range<usize>,
+}
+
+impl Chunk {
+    /// Number of source tokens covered by this chunk.
+    pub fn len(&self) -> usize {
+        self.token_range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.token_range.is_empty()
+    }
+}
+
+/// Half-open token ranges for the sliding windows over `len` token ids.
+///
+/// Factored out from [`chunk_text`] so the off-by-one-prone indexing — window
+/// end clamp, `step = chunk_tokens - overlap_tokens`, last-window break — can be
+/// exercised without a tokenizer. `overlap_tokens >= chunk_tokens` is a caller
+/// error (guarded by `debug_assert!` in `chunk_text`); should it slip through in
+/// release, `step` is floored at 1 so the walk still terminates instead of
+/// spinning forever.
+fn window_ranges(len: usize, config: ChunkConfig) -> Vec<Range<usize>> {
+    if len == 0 {
+        return Vec::new();
+    }
+
+    let step = config.chunk_tokens.saturating_sub(config.overlap_tokens).max(1);
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < len {
+        let end = (start + config.chunk_tokens).min(len);
+        ranges.push(start..end);
+        if end == len {
+            break;
+        }
+        start += step;
+    }
+
+    ranges
+}
+
+/// Tokenize `text` once and slide a `chunk_tokens`-wide window over the token
+/// ids with `overlap_tokens` of overlap, decoding each window back to text.
+pub fn chunk_text(text: &str, tokenizer: &Tokenizer, config: ChunkConfig) -> Result<Vec<Chunk>> {
+    debug_assert!(
+        config.overlap_tokens < config.chunk_tokens,
+        "overlap_tokens must be smaller than chunk_tokens"
+    );
+
+    let encoding = tokenizer.encode(text, false).map_err(Error::msg)?;
+    let ids = encoding.get_ids();
+
+    window_ranges(ids.len(), config)
+        .into_iter()
+        .map(|range| {
+            let text = tokenizer.decode(&ids[range.clone()], true).map_err(Error::msg)?;
+            Ok(Chunk {
+                text,
+                token_range: range,
+            })
+        })
+        .collect()
+}
+
+/// Per-chunk embeddings of a long document, retaining each chunk's source token
+/// range so callers can map a vector back to its position in the document.
+pub struct ChunkedEmbeddings {
+    /// `[n_chunks, hidden_size]` embeddings, one row per chunk.
+    pub embeddings: Tensor,
+    pub chunks: Vec<Chunk>,
+}
+
+impl ChunkedEmbeddings {
+    /// Collapse the per-chunk embeddings into a single unit-norm vector,
+    /// weighting each chunk by the number of source tokens it covers.
+    ///
+    /// The weighted sum of (possibly already normalized) chunk vectors is not
+    /// itself unit-norm, so the result is L2-normalized before returning to stay
+    /// consistent with the normalized vectors the rest of the API produces.
+    pub fn length_weighted_mean(&self) -> Result<Tensor> {
+        let weights: Vec<f32> = self.chunks.iter().map(|c| c.len() as f32).collect();
+        let total: f32 = weights.iter().sum();
+        let weights = Tensor::new(weights.as_slice(), self.embeddings.device())?.unsqueeze(1)?;
+        let weighted = self.embeddings.broadcast_mul(&weights)?.sum(0)?;
+        let mean = (weighted / total.max(1.0) as f64)?;
+        Ok(normalize_l2(&mean.unsqueeze(0)?)?.squeeze(0)?)
+    }
+}
+
+/// Chunk `text` into token-bounded windows and embed each window with
+/// `encode_batch`, returning the per-chunk vectors alongside their source
+/// ranges.
+///
+/// This is the primitive behind [`SentenceTransformer::encode_long`], which
+/// simply passes its already-loaded model and tokenizer here.
+pub fn encode_long(
+    model: &dyn EmbedderModel,
+    tokenizer: &Tokenizer,
+    text: &str,
+    config: ChunkConfig,
+    normalize: bool,
+) -> Result<ChunkedEmbeddings> {
+    let chunks = chunk_text(text, tokenizer, config)?;
+    if chunks.is_empty() {
+        // No tokens means no windows, and `encode_batch_with_usage` would fail
+        // stacking an empty batch — reject it with a clear error instead.
+        return Err(Error::msg("cannot embed empty text"));
+    }
+    let sentences: Vec<String> = chunks.iter().map(|c| c.text.clone()).collect();
+    let (embeddings, _usage) = encode_batch_with_usage(
+        model,
+        tokenizer,
+        sentences,
+        normalize,
+        PoolingMode::default(),
+    )?;
+    Ok(ChunkedEmbeddings { embeddings, chunks })
+}
+
+impl<M> SentenceTransformer<M> {
+    /// Embed a document longer than the model's context window by splitting it
+    /// into overlapping, token-bounded windows per `config`, embedding each
+    /// window, and returning the per-chunk vectors alongside their source token
+    /// ranges.
+    ///
+    /// Collapse the result into a single document vector with
+    /// [`ChunkedEmbeddings::length_weighted_mean`].
+    ///
+    /// `normalize` L2-normalizes each per-chunk vector, matching
+    /// [`SentenceTransformer::encode_batch`].
+    pub fn encode_long(
+        &self,
+        text: &str,
+        config: ChunkConfig,
+        normalize: bool,
+    ) -> Result<ChunkedEmbeddings> {
+        encode_long(self.model(), self.tokenizer(), text, config, normalize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_overlap_across_several_windows() {
+        let ranges = window_ranges(10, ChunkConfig::new(4, 1));
+        // step = 4 - 1 = 3, so starts walk 0, 3, 6; the 6..10 window hits the
+        // end and breaks.
+        assert_eq!(ranges, vec![0..4, 3..7, 6..10]);
+        // Successive windows share exactly `overlap_tokens` ids.
+        for pair in ranges.windows(2) {
+            if pair[1].start < pair[0].end {
+                assert_eq!(pair[0].end - pair[1].start, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn exact_multiple_has_no_remainder_tail() {
+        // step = 2, length 6: 0..4, 2..6 — the second window ends exactly at len.
+        let ranges = window_ranges(6, ChunkConfig::new(4, 2));
+        assert_eq!(ranges, vec![0..4, 2..6]);
+    }
+
+    #[test]
+    fn remainder_tail_is_kept() {
+        // step = 2, length 7: the final 6..7 tail must not be dropped.
+        let ranges = window_ranges(7, ChunkConfig::new(4, 2));
+        assert_eq!(ranges, vec![0..4, 2..6, 4..7]);
+    }
+
+    #[test]
+    fn single_token_yields_one_window() {
+        assert_eq!(window_ranges(1, ChunkConfig::default()), vec![0..1]);
+    }
+
+    #[test]
+    fn empty_input_yields_no_windows() {
+        assert!(window_ranges(0, ChunkConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn overlap_not_smaller_than_chunk_still_terminates() {
+        // Invalid config (guarded only by `debug_assert!` in `chunk_text`): step
+        // floors at 1 so the walk advances one id at a time instead of looping.
+        let ranges = window_ranges(3, ChunkConfig::new(2, 5));
+        assert_eq!(ranges, vec![0..2, 1..3]);
+    }
+}
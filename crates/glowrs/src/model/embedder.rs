@@ -1,5 +1,5 @@
 use anyhow::{Context, Error, Result};
-use candle_core::{DType, Module, Tensor};
+use candle_core::{DType, IndexOp, Module, Tensor};
 use candle_nn::VarBuilder;
 use candle_transformers::models::{
     bert::Config as BertConfig, jina_bert::Config as JinaBertConfig,
@@ -67,16 +67,29 @@ impl EmbedderModel for JinaBertModel {
     }
 }
 
+/// Format of the on-disk weights to load for a model.
+///
+/// HF repositories are inconsistent about what they ship: newer
+/// `sentence-transformers`/BGE exports carry `model.safetensors`, while a large
+/// fraction of older ones only ship the original `pytorch_model.bin`.
+/// [`Safetensors`](Self::Safetensors) is the default and transparently falls
+/// back to `pytorch_model.bin` when the repository doesn't contain a
+/// safetensors file, whereas [`Pytorch`](Self::Pytorch) always loads the
+/// PyTorch weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+pub enum WeightSource {
+    #[default]
+    Safetensors,
+    Pytorch,
+}
+
 pub(crate) fn load_model_and_tokenizer_gen<L>(
     api: ApiRepo,
+    weight_source: WeightSource,
 ) -> Result<(Box<dyn EmbedderModel>, Tokenizer)>
 where
     L: LoadableModel,
 {
-    let model_path = api
-        .get("model.safetensors")
-        .context("Model repository is not available or doesn't contain `model.safetensors`.")?;
-
     let config_path = api
         .get("config.json")
         .context("Model repository doesn't contain `config.json`.")?;
@@ -93,14 +106,52 @@ where
 			"Failed to deserialize config.json. Make sure you have the right EmbedderModel implementation."
 		)?;
 
-    let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[model_path], DType::F32, &DEVICE)? };
+    // Prefer safetensors, but fall back to PyTorch weights when the repo only
+    // ships `pytorch_model.bin` so arbitrary embedding repos load unchanged.
+    // A failed `api.get("model.safetensors")` can't drive that decision: it
+    // would also swallow a transient network/HTTP error as "file absent" and
+    // surface a misleading "doesn't contain `pytorch_model.bin`" error. Consult
+    // the repo listing instead, which tells a genuinely missing file apart from
+    // a fetch that merely failed.
+    let source = match weight_source {
+        WeightSource::Safetensors => {
+            let info = api
+                .info()
+                .context("Failed to list model repository files.")?;
+            let has_safetensors = info
+                .siblings
+                .iter()
+                .any(|sibling| sibling.rfilename == "model.safetensors");
+            if has_safetensors {
+                WeightSource::Safetensors
+            } else {
+                WeightSource::Pytorch
+            }
+        }
+        other => other,
+    };
+
+    let vb = match source {
+        WeightSource::Safetensors => {
+            let model_path = api.get("model.safetensors").context(
+                "Model repository is not available or doesn't contain `model.safetensors`.",
+            )?;
+            unsafe { VarBuilder::from_mmaped_safetensors(&[model_path], DType::F32, &DEVICE)? }
+        }
+        WeightSource::Pytorch => {
+            let model_path = api
+                .get("pytorch_model.bin")
+                .context("Model repository doesn't contain `pytorch_model.bin`.")?;
+            VarBuilder::from_pth(model_path, DType::F32, &DEVICE)?
+        }
+    };
 
     let model = L::load_model(vb, &cfg).context("Something went wrong while loading the model.")?;
 
     Ok((model, tokenizer))
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
 pub enum EmbedderType {
     Bert,
     JinaBert,
@@ -109,27 +160,47 @@ pub enum EmbedderType {
 pub(crate) fn load_model_and_tokenizer(
     api: ApiRepo,
     embedder_type: EmbedderType,
+    weight_source: WeightSource,
 ) -> Result<(Box<dyn EmbedderModel>, Tokenizer)> {
     let (model, tokenizer) = match embedder_type {
-        EmbedderType::Bert => load_model_and_tokenizer_gen::<BertModel>(api)?,
-        EmbedderType::JinaBert => load_model_and_tokenizer_gen::<JinaBertModel>(api)?,
+        EmbedderType::Bert => load_model_and_tokenizer_gen::<BertModel>(api, weight_source)?,
+        EmbedderType::JinaBert => load_model_and_tokenizer_gen::<JinaBertModel>(api, weight_source)?,
     };
     Ok((model, tokenizer))
 }
 
+/// Strategy used to pool a `[n_sentence, seq_len, hidden_size]` hidden state
+/// into a single `[n_sentence, hidden_size]` embedding.
+///
+/// The masked variants use the tokenizer's attention mask so padding positions
+/// of uneven-length batches don't leak into the pooled vector; pick the one the
+/// model card recommends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoolingMode {
+    /// Mean over real tokens only, using the attention mask.
+    #[default]
+    MeanWithMask,
+    /// Take the `[CLS]` token (position 0).
+    Cls,
+    /// Max over real tokens only, using the attention mask.
+    MaxWithMask,
+    /// Unmasked mean over every position, including padding. Kept only for
+    /// backwards compatibility with the original pooling behaviour.
+    MeanUnmasked,
+}
+
 pub(crate) fn encode_batch_with_usage(
     model: &dyn EmbedderModel,
     tokenizer: &Tokenizer,
     sentences: impl Into<Vec<String>>,
     normalize: bool,
+    pooling_mode: PoolingMode,
 ) -> Result<(Tensor, Usage)> {
     let tokens = tokenizer
         .encode_batch(sentences.into(), true)
         .map_err(Error::msg)
         .context("Failed to encode batch.")?;
 
-    let prompt_tokens = tokens.len() as u32;
-
     let token_ids = tokens
         .iter()
         .map(|tokens| {
@@ -140,34 +211,80 @@ pub(crate) fn encode_batch_with_usage(
 
     let token_ids = Tensor::stack(&token_ids, 0)?;
 
+    // Stack the attention masks into a `[n_sentence, seq_len]` tensor so padding
+    // positions can be excluded from both pooling and the usage count.
+    let attention_mask = tokens
+        .iter()
+        .map(|tokens| {
+            let mask = tokens.get_attention_mask().to_vec();
+            Tensor::new(mask.as_slice(), &DEVICE)
+        })
+        .collect::<candle_core::Result<Vec<_>>>()?;
+    let attention_mask = Tensor::stack(&attention_mask, 0)?.to_dtype(DType::F32)?;
+
     tracing::trace!("running inference on batch {:?}", token_ids.shape());
     let embeddings = model.inner_forward(&token_ids)?;
     tracing::trace!("generated embeddings {:?}", embeddings.shape());
 
-    // Apply some avg-pooling by taking the mean model value for all tokens (including padding)
-    let (_n_sentence, out_tokens, _hidden_size) = embeddings.dims3()?;
-    let embeddings = (embeddings.sum(1)? / (out_tokens as f64))?;
+    let embeddings = pool(&embeddings, &attention_mask, pooling_mode)?;
+
     let embeddings = if normalize {
         normalize_l2(&embeddings)?
     } else {
         embeddings
     };
 
-    // TODO: Incorrect usage calculation - fix
+    // Count real (non-pad) tokens from the attention mask.
+    let prompt_tokens = attention_mask.sum_all()?.to_scalar::<f32>()? as u32;
     let usage = Usage {
         prompt_tokens,
-        total_tokens: prompt_tokens + (out_tokens as u32),
+        total_tokens: prompt_tokens,
     };
     Ok((embeddings, usage))
 }
 
+/// Pool a `[n_sentence, seq_len, hidden_size]` hidden state down to one
+/// `[n_sentence, hidden_size]` embedding per the selected [`PoolingMode`].
+///
+/// `attention_mask` is the `[n_sentence, seq_len]` mask from the tokenizer; the
+/// masked modes use it to keep padding positions of uneven-length batches out of
+/// the pooled vector.
+fn pool(
+    embeddings: &Tensor,
+    attention_mask: &Tensor,
+    pooling_mode: PoolingMode,
+) -> Result<Tensor> {
+    let (_n_sentence, out_tokens, _hidden_size) = embeddings.dims3()?;
+    // `[n_sentence, seq_len, 1]` so it broadcasts against the hidden states.
+    let mask = attention_mask.unsqueeze(2)?;
+
+    let pooled = match pooling_mode {
+        PoolingMode::MeanWithMask => {
+            let masked = embeddings.broadcast_mul(&mask)?;
+            let summed = masked.sum(1)?;
+            let counts = mask.sum(1)?.clamp(1f64, f64::INFINITY)?;
+            summed.broadcast_div(&counts)?
+        }
+        PoolingMode::Cls => embeddings.i((.., 0))?,
+        PoolingMode::MaxWithMask => {
+            // Push padding positions to a large negative value before the max.
+            let neg = ((mask.affine(-1.0, 1.0)?) * -1e9)?;
+            embeddings.broadcast_add(&neg)?.max(1)?
+        }
+        PoolingMode::MeanUnmasked => (embeddings.sum(1)? / (out_tokens as f64))?,
+    };
+
+    Ok(pooled)
+}
+
 pub(crate) fn encode_batch(
     model: &dyn EmbedderModel,
     tokenizer: &Tokenizer,
     sentences: Sentences,
     normalize: bool,
 ) -> Result<Tensor> {
-    let (out, _) = encode_batch_with_usage(model, tokenizer, sentences, normalize)?;
+    let (out, _) =
+        encode_batch_with_usage(model, tokenizer, sentences, normalize, PoolingMode::default())?;
     Ok(out)
 }
 
@@ -187,6 +304,56 @@ mod tests {
             RepoType::Model,
             revision.into(),
         ));
-        let (_model, _tokenizer) = load_model_and_tokenizer_gen::<BertModel>(api).unwrap();
+        let (_model, _tokenizer) =
+            load_model_and_tokenizer_gen::<BertModel>(api, WeightSource::default()).unwrap();
+    }
+
+    // A `[2, 3, 2]` hidden state whose second sentence has one padding position,
+    // flagged by the `[[1,1,1],[1,1,0]]` attention mask. The padding row carries
+    // a large value so masked and unmasked pooling are easy to tell apart.
+    fn uneven_batch() -> (Tensor, Tensor) {
+        let hidden = Tensor::from_vec(
+            vec![5f32, 5., 5., 5., 5., 5., 1., 1., 3., 3., 100., 100.],
+            (2, 3, 2),
+            &DEVICE,
+        )
+        .unwrap();
+        let mask = Tensor::from_vec(vec![1f32, 1., 1., 1., 1., 0.], (2, 3), &DEVICE).unwrap();
+        (hidden, mask)
+    }
+
+    #[test]
+    fn mean_with_mask_excludes_padding() {
+        let (hidden, mask) = uneven_batch();
+        let masked = pool(&hidden, &mask, PoolingMode::MeanWithMask)
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        // Second sentence averages only its two real tokens: (1+3)/2 = 2.
+        assert_eq!(masked, vec![vec![5.0, 5.0], vec![2.0, 2.0]]);
+    }
+
+    #[test]
+    fn mean_with_mask_differs_from_unmasked_on_uneven_batch() {
+        let (hidden, mask) = uneven_batch();
+        let masked = pool(&hidden, &mask, PoolingMode::MeanWithMask)
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        let unmasked = pool(&hidden, &mask, PoolingMode::MeanUnmasked)
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        // The padded sentence diverges; the fully-real one is unchanged.
+        assert_eq!(masked[0], unmasked[0]);
+        assert_ne!(masked[1], unmasked[1]);
+    }
+
+    #[test]
+    fn prompt_tokens_count_excludes_padding() {
+        let (_hidden, mask) = uneven_batch();
+        let prompt_tokens = mask.sum_all().unwrap().to_scalar::<f32>().unwrap() as u32;
+        // Five real tokens across the batch, the sixth position is padding.
+        assert_eq!(prompt_tokens, 5);
     }
 }
@@ -0,0 +1,39 @@
+use std::time::Instant;
+
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::infer::handler::RequestHandler;
+
+/// A single queued request together with the channel its response is sent back
+/// over and the bookkeeping used for tracing and metrics.
+pub(crate) struct QueueEntry<THandler>
+where
+    THandler: RequestHandler,
+{
+    /// Unique identifier for the entry.
+    pub id: Uuid,
+    /// The request payload handed to the processor.
+    pub request: THandler::TReq,
+    /// Channel the processed response is returned over.
+    pub response_tx: oneshot::Sender<THandler::TResp>,
+    /// Instant the entry was enqueued, used to derive queue latency.
+    pub queue_time: Instant,
+}
+
+impl<THandler> QueueEntry<THandler>
+where
+    THandler: RequestHandler,
+{
+    pub(crate) fn new(
+        request: THandler::TReq,
+        response_tx: oneshot::Sender<THandler::TResp>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            request,
+            response_tx,
+            queue_time: Instant::now(),
+        }
+    }
+}
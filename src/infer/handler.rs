@@ -0,0 +1,40 @@
+use anyhow::Result;
+
+/// Stateful processor for a single queue.
+///
+/// A handler owns whatever resources it needs to turn a request into a
+/// response (e.g. a loaded model and tokenizer) and is driven exclusively from
+/// the queue's background task, so it does not need to be `Sync`.
+pub trait RequestHandler: Send + 'static {
+    type TReq: Send;
+    type TResp: Send;
+
+    /// Process a single request.
+    fn handle(&mut self, request: Self::TReq) -> Result<Self::TResp>;
+
+    /// Process a batch of requests, returning exactly one result per request in
+    /// the same order as `requests`.
+    ///
+    /// Defaults to looping over [`handle`](Self::handle), which keeps a single
+    /// request's failure isolated to its own entry. Handlers backed by a model
+    /// that benefits from batched inference should override this to run the
+    /// whole batch through a single forward pass — an override **must** still
+    /// yield one [`Result`] per request, in order: the queue pairs results with
+    /// callers by position, and drops the whole batch without responding when
+    /// the number of results doesn't match the number of requests.
+    fn handle_batch(&mut self, requests: Vec<Self::TReq>) -> Vec<Result<Self::TResp>> {
+        requests
+            .into_iter()
+            .map(|request| self.handle(request))
+            .collect()
+    }
+
+    /// Report the token usage of a processed response as
+    /// `(prompt_tokens, total_tokens)`.
+    ///
+    /// Used only for metrics instrumentation; defaults to `None` for handlers
+    /// that don't carry usage information.
+    fn response_usage(_response: &Self::TResp) -> Option<(u32, u32)> {
+        None
+    }
+}
@@ -0,0 +1,56 @@
+//! Optional Prometheus-style instrumentation for the inference queue.
+//!
+//! Everything here routes through the [`metrics`] crate facade, so any exporter
+//! (Prometheus, StatsD, …) installed by the embedding application picks the
+//! series up. When the `metrics` feature is disabled every function compiles
+//! down to a no-op, leaving the hot path untouched.
+
+/// Record the number of requests flushed together in a single batch.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_queue_depth(depth: usize) {
+    metrics::gauge!("glowrs_queue_depth").set(depth as f64);
+}
+
+/// Record the wall-clock time a batch spent inside `handle_batch` — the model
+/// forward pass itself, excluding any time requests waited in the queue.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_processing_latency(seconds: f64) {
+    metrics::histogram!("glowrs_queue_processing_latency_seconds").record(seconds);
+}
+
+/// Record the time a request spent waiting in the queue before its batch
+/// started processing.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_queue_wait(seconds: f64) {
+    metrics::histogram!("glowrs_queue_wait_seconds").record(seconds);
+}
+
+/// Record the number of tokens processed for a request.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_tokens(prompt_tokens: u32, total_tokens: u32) {
+    metrics::counter!("glowrs_queue_prompt_tokens_total").increment(prompt_tokens as u64);
+    metrics::counter!("glowrs_queue_total_tokens_total").increment(total_tokens as u64);
+}
+
+/// Record whether a processed response was successfully handed back to its
+/// caller.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_response_sent(success: bool) {
+    let status = if success { "ok" } else { "dropped" };
+    metrics::counter!("glowrs_queue_responses_total", "status" => status).increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_queue_depth(_depth: usize) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_processing_latency(_seconds: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_queue_wait(_seconds: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_tokens(_prompt_tokens: u32, _total_tokens: u32) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_response_sent(_success: bool) {}
@@ -1,9 +1,13 @@
+use std::time::{Duration, Instant};
+
 use anyhow::Result;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::timeout_at;
 use uuid::Uuid;
 
 use crate::infer::batch::QueueEntry;
 use crate::infer::handler::RequestHandler;
+use crate::infer::metrics;
 
 /// Queue command
 #[allow(dead_code)]
@@ -28,7 +32,18 @@ impl<THandler> Queue<THandler>
 where
     THandler: RequestHandler
 {
-    pub(crate) fn new(processor: THandler) -> Result<Self> {
+    /// Spawn a queue whose background task coalesces pending requests into
+    /// batches.
+    ///
+    /// A batch is flushed as soon as `max_batch_size` requests have
+    /// accumulated, or `max_wait` has elapsed since the first request in the
+    /// batch arrived — whichever comes first — bounding the latency a single
+    /// request pays while still exploiting the batch dimension under load.
+    pub(crate) fn new(
+        processor: THandler,
+        max_batch_size: usize,
+        max_wait: Duration,
+    ) -> Result<Self> {
 
         // Create channel
         let (queue_tx, queue_rx) = unbounded_channel();
@@ -42,7 +57,7 @@ where
                 .build()?;
 
             // Pull task requests off the channel and send them to the executor
-            runtime.block_on(queue_task(queue_rx, processor))
+            runtime.block_on(queue_task(queue_rx, processor, max_batch_size, max_wait))
         });
 
         Ok(Self {
@@ -51,68 +66,163 @@ where
     }
 }
 
-// Generic background task executor with stateful processor
+// Generic background task executor with stateful processor.
+//
+// Accumulates `Append` commands into a pending buffer and flushes it through a
+// single `handle_batch` call when `max_batch_size` is reached or `max_wait`
+// elapses since the first buffered request.
 async fn queue_task<THandler>(
     mut receiver: UnboundedReceiver<QueueCommand<THandler>>,
     mut processor: THandler,
+    max_batch_size: usize,
+    max_wait: Duration,
 ) -> Result<()>
 where
     THandler: RequestHandler
 {
-    'main: while let Some(cmd) = receiver.recv().await {
-        use QueueCommand::*;
-
-        match cmd {
-            Append(entry) => {
-                tracing::trace!(
-                    "Processing task {}, added {}ms ago",
-                    entry.id,
-                    entry.queue_time.elapsed().as_millis()
-                );
-
-                // Process the task
-                let response = processor.handle(entry.request)?;
-
-                if entry.response_tx.send(response).is_ok() {
-                    tracing::trace!("Successfully sent response for task {}", entry.id)
-                } else {
-                    tracing::error!("Failed to send response for task {}", entry.id)
-                }
-            }
-            Stop => {
+    use QueueCommand::*;
+
+    'main: loop {
+        // Block until the first request of the next batch arrives.
+        let mut buffer = match receiver.recv().await {
+            Some(Append(entry)) => vec![entry],
+            Some(Stop) | None => {
                 tracing::info!("Stopping queue task");
                 break 'main;
             }
+        };
+
+        // Bound the batch latency from the moment the first request landed.
+        let deadline = tokio::time::Instant::now() + max_wait;
+        let mut stop = false;
+        while buffer.len() < max_batch_size {
+            match timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(Append(entry))) => buffer.push(entry),
+                Ok(Some(Stop)) | Ok(None) => {
+                    stop = true;
+                    break;
+                }
+                // Deadline hit: flush whatever we have.
+                Err(_) => break,
+            }
+        }
+
+        flush_batch(&mut processor, buffer);
+
+        if stop {
+            tracing::info!("Stopping queue task");
+            break 'main;
         }
     }
     Ok(())
 }
 
+// Run a buffered batch through the processor and fan the responses back out to
+// each entry's response channel.
+fn flush_batch<THandler>(processor: &mut THandler, buffer: Vec<QueueEntry<THandler>>)
+where
+    THandler: RequestHandler,
+{
+    if buffer.is_empty() {
+        return;
+    }
+
+    tracing::trace!(
+        "Processing batch of {}, oldest added {}ms ago",
+        buffer.len(),
+        buffer
+            .iter()
+            .map(|entry| entry.queue_time.elapsed().as_millis())
+            .max()
+            .unwrap_or(0)
+    );
+
+    metrics::record_queue_depth(buffer.len());
+
+    let (requests, channels): (Vec<_>, Vec<_>) = buffer
+        .into_iter()
+        .map(|entry| (entry.request, (entry.id, entry.queue_time, entry.response_tx)))
+        .unzip();
+
+    // Time only the forward pass so the latency series reflects processing
+    // cost, not how long requests sat in the queue beforehand.
+    let processing_start = Instant::now();
+
+    // Record queue wait as the time from enqueue up to the start of processing,
+    // before `handle_batch` runs. Measuring it afterwards would fold the whole
+    // batch's forward-pass time into the wait series and double-count it against
+    // the processing-latency series.
+    for (_, queue_time, _) in &channels {
+        metrics::record_queue_wait(processing_start.duration_since(*queue_time).as_secs_f64());
+    }
+
+    let results = processor.handle_batch(requests);
+    metrics::record_processing_latency(processing_start.elapsed().as_secs_f64());
+
+    // `handle_batch` must return one result per request, in order. A length
+    // mismatch means a buggy override; drop the batch loudly rather than
+    // silently pairing responses with the wrong callers.
+    if results.len() != channels.len() {
+        tracing::error!(
+            "handle_batch returned {} results for {} requests; dropping batch",
+            results.len(),
+            channels.len()
+        );
+        return;
+    }
+
+    for ((id, _queue_time, response_tx), result) in channels.into_iter().zip(results) {
+        match result {
+            Ok(response) => {
+                if let Some((prompt_tokens, total_tokens)) = THandler::response_usage(&response) {
+                    metrics::record_tokens(prompt_tokens, total_tokens);
+                }
+                if response_tx.send(response).is_ok() {
+                    metrics::record_response_sent(true);
+                    tracing::trace!("Successfully sent response for task {}", id)
+                } else {
+                    metrics::record_response_sent(false);
+                    tracing::error!("Failed to send response for task {}", id)
+                }
+            }
+            // Only this request failed; dropping its channel surfaces the error
+            // to its own caller without disturbing the rest of the batch.
+            Err(err) => {
+                metrics::record_response_sent(false);
+                tracing::error!("Failed to process task {}: {}", id, err);
+            }
+        }
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::anyhow;
     use tokio::sync::oneshot;
     use super::*;
-    
+
     #[derive(Debug, PartialEq)]
     struct Task {
         name: String,
     }
-    
+
     impl Task {
         fn new(name: String) -> Self {
             Self { name }
         }
     }
-    
+
     struct TaskProcessor;
-    
+
     impl TaskProcessor {
          fn new() -> Result<Self> {
             Ok(Self)
         }
     }
-    
+
     impl RequestHandler for TaskProcessor {
         type TReq = Task;
         type TResp = Task;
@@ -124,13 +234,80 @@ mod tests {
         }
     }
 
+    // Records the size of every batch it is handed so tests can assert how
+    // requests were coalesced, then processes each with the default contract.
+    struct RecordingProcessor {
+        batches: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl RequestHandler for RecordingProcessor {
+        type TReq = Task;
+        type TResp = Task;
+
+        fn handle(&mut self, request: Task) -> Result<Task> {
+            Ok(Task::new(format!("{}-processed", request.name)))
+        }
+
+        fn handle_batch(&mut self, requests: Vec<Task>) -> Vec<Result<Task>> {
+            self.batches.lock().unwrap().push(requests.len());
+            requests.into_iter().map(|r| self.handle(r)).collect()
+        }
+    }
+
+    // Fails the request named `boom` and processes every other request, leaning
+    // on the default `handle_batch` so one error can't poison its batch mates.
+    struct PartiallyFailingProcessor;
+
+    impl RequestHandler for PartiallyFailingProcessor {
+        type TReq = Task;
+        type TResp = Task;
+
+        fn handle(&mut self, request: Task) -> Result<Task> {
+            if request.name == "boom" {
+                return Err(anyhow!("request failed"));
+            }
+            Ok(Task::new(format!("{}-processed", request.name)))
+        }
+    }
+
+    // Overrides `handle_batch` to break the one-result-per-request contract so
+    // the length-mismatch guard in `flush_batch` can be exercised.
+    struct WrongLengthProcessor;
+
+    impl RequestHandler for WrongLengthProcessor {
+        type TReq = Task;
+        type TResp = Task;
+
+        fn handle(&mut self, request: Task) -> Result<Task> {
+            Ok(Task::new(format!("{}-processed", request.name)))
+        }
+
+        fn handle_batch(&mut self, _requests: Vec<Task>) -> Vec<Result<Task>> {
+            // Fewer results than requests: a buggy override.
+            Vec::new()
+        }
+    }
+
+    fn enqueue<THandler>(queue: &Queue<THandler>, name: &str) -> oneshot::Receiver<Task>
+    where
+        THandler: RequestHandler<TReq = Task, TResp = Task>,
+    {
+        let (tx, rx) = oneshot::channel();
+        queue
+            .tx
+            .send(QueueCommand::Append(QueueEntry::new(Task::new(name.into()), tx)))
+            .unwrap();
+        rx
+    }
+
     #[tokio::test]
     async fn test_queue() {
         // Create a new processor
         let processor = TaskProcessor::new().unwrap();
         
         // Create a new queue
-        let queue: Queue<TaskProcessor> = Queue::new(processor).unwrap();
+        let queue: Queue<TaskProcessor> =
+            Queue::new(processor, 32, Duration::from_millis(5)).unwrap();
 
         // Set a task name
         let name = "test".to_string();
@@ -146,4 +323,66 @@ mod tests {
         let response = task_rx.await.unwrap();
         assert_eq!(response, Task::new(format!("{}-processed", name).to_string()));
     }
+
+    #[tokio::test]
+    async fn test_requests_are_coalesced_into_one_batch() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let processor = RecordingProcessor {
+            batches: batches.clone(),
+        };
+        // A generous batch size and wait so all three requests land in the same
+        // window and flush together once the deadline elapses.
+        let queue: Queue<RecordingProcessor> =
+            Queue::new(processor, 32, Duration::from_millis(50)).unwrap();
+
+        let rxs: Vec<_> = ["a", "b", "c"].iter().map(|n| enqueue(&queue, n)).collect();
+
+        let mut responses = Vec::new();
+        for rx in rxs {
+            responses.push(rx.await.unwrap());
+        }
+        responses.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(
+            responses,
+            vec![
+                Task::new("a-processed".into()),
+                Task::new("b-processed".into()),
+                Task::new("c-processed".into()),
+            ]
+        );
+        // The three requests went through as a single `handle_batch` call.
+        assert_eq!(*batches.lock().unwrap(), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn test_one_failing_request_does_not_poison_the_batch() {
+        let queue: Queue<PartiallyFailingProcessor> =
+            Queue::new(PartiallyFailingProcessor, 32, Duration::from_millis(50)).unwrap();
+
+        let ok1 = enqueue(&queue, "ok1");
+        let boom = enqueue(&queue, "boom");
+        let ok2 = enqueue(&queue, "ok2");
+
+        // The healthy requests still get their responses...
+        assert_eq!(ok1.await.unwrap(), Task::new("ok1-processed".into()));
+        assert_eq!(ok2.await.unwrap(), Task::new("ok2-processed".into()));
+        // ...while the failed one's channel is dropped, surfacing the error only
+        // to its own caller.
+        assert!(boom.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_length_mismatch_drops_the_whole_batch() {
+        let queue: Queue<WrongLengthProcessor> =
+            Queue::new(WrongLengthProcessor, 32, Duration::from_millis(50)).unwrap();
+
+        let a = enqueue(&queue, "a");
+        let b = enqueue(&queue, "b");
+
+        // A result/request count mismatch drops the batch rather than mis-pairing
+        // responses, so every caller's channel closes without a response.
+        assert!(a.await.is_err());
+        assert!(b.await.is_err());
+    }
 }